@@ -1,31 +1,47 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use rand::{rngs::OsRng, seq::SliceRandom, Rng, RngCore};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::process::{ExitCode, Termination};
 use std::thread;
 use std::time::Duration;
 use zeppelin_core::cipher::CryptSettings;
-use zeppelin_core::container::{create_container, read_container};
+use zeppelin_core::container::{change_password, create_container, read_container};
 use zeppelin_core::progress::{self, Progress};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Encryption level presets, mirroring the choices offered by the interactive prompt.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Level {
+    Weak,
+    Default,
+    Strong,
+    Custom,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
 struct Args {
-    /// Path to file to encrypt
-    file: PathBuf,
+    /// Path to file to encrypt, `-` or omitted to read from stdin
+    file: Option<PathBuf>,
     #[clap(long, short, value_parser)]
     /// File used in combination with key
     key_file: Option<PathBuf>,
-    #[clap(long, short = 'l', value_parser)]
+    #[clap(long, short = 'l', value_enum)]
     /// Encryption level
-    level: Option<String>,
+    level: Option<Level>,
     #[clap(long, value_parser)]
     /// Memory scaling factor of encryption
     s_cost: Option<u64>,
     #[clap(long, value_parser)]
     /// Time scaling factor of encryption
     t_cost: Option<u64>,
+    #[clap(long, value_parser)]
+    /// Step delta of encryption, only used with `--level custom` or when `--level` is omitted
+    step_delta: Option<u64>,
     /// Output file name
     output: Option<PathBuf>,
     #[clap(short, long, value_parser)]
@@ -34,6 +50,24 @@ struct Args {
     #[clap(short = 'r', value_parser)]
     /// Erase original file
     erase: bool,
+    #[clap(short = 'p', long, value_parser)]
+    /// Password to use, instead of prompting for one
+    password: Option<String>,
+    #[clap(short = 'y', long, value_parser)]
+    /// Automatically confirm overwriting the output file
+    yes: bool,
+    #[clap(long, value_parser)]
+    /// Rotate the container's password in place, without re-encrypting its contents
+    change_password: bool,
+    #[clap(long, value_parser)]
+    /// New password to set, used with `--change-password`; prompted for when absent
+    new_password: Option<String>,
+    #[clap(long, value_parser, num_args = 0..=1, default_missing_value = "20")]
+    /// Generate a random password of the given length instead of prompting for one
+    generate_password: Option<usize>,
+    #[clap(long, value_parser, requires = "generate_password")]
+    /// Draw the generated password as space-joined words from this wordlist, instead of characters
+    diceware: Option<PathBuf>,
 }
 
 /// Returns stdout to a clean state.
@@ -51,6 +85,9 @@ enum MainStatus {
     Ok,
     Err(String),
     FileNotFound(PathBuf),
+    EraseFailed(String),
+    InvalidPassword,
+    KeyFileRequired,
 }
 
 impl Termination for MainStatus {
@@ -69,6 +106,22 @@ impl Termination for MainStatus {
                 eprintln!("File {:?} not found!", file);
                 ExitCode::from(2)
             }
+            MainStatus::EraseFailed(reason) => {
+                eprintln!(
+                    "Encryption succeeded, but securely erasing the original failed: {}. \
+                     The plaintext may still be recoverable!",
+                    reason
+                );
+                ExitCode::from(3)
+            }
+            MainStatus::InvalidPassword => {
+                eprintln!("Invalid password!");
+                ExitCode::from(4)
+            }
+            MainStatus::KeyFileRequired => {
+                eprintln!("This container was created with a key file; pass it via --key-file!");
+                ExitCode::from(5)
+            }
         }
     }
 }
@@ -80,126 +133,578 @@ fn append_extension(path: &mut PathBuf, ext: impl AsRef<std::ffi::OsStr>) {
     *path = os_string.into()
 }
 
-// fn run_thread<R: Read + Seek>(
-//     source: &mut R,
-//     output: Option<PathBuf>,
-//     key: String,
-//     decrypt: bool,
-//     progress: Progress,
-// ) -> MainStatus {
-//     let res = if decrypt {
-//         read_container(source, dest, key, progress)
-//     } else {
-//         create_container(source, dest, key, settings, progress)
-//     };
-
-//     MainStatus::Ok
-// }
+/// A reader/seeker that hides the first `offset` bytes of the wrapped stream, so a small
+/// fixed-size header can be prepended to a container without the container format itself
+/// knowing about it. Only `SeekFrom::Start` needs adjusting: `Current` is already relative,
+/// and `End` is unaffected since the header sits at the front, not the back.
+struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
 
-fn main() -> MainStatus {
-    let args = Args::parse();
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
 
-    // Check if given file exists
-    let file = args.file.clone();
-    if !file.exists() {
-        return MainStatus::FileNotFound(file);
+impl<R: Seek> Seek for OffsetReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => SeekFrom::Start(n + self.offset),
+            other => other,
+        };
+        let actual = self.inner.seek(pos)?;
+        Ok(actual.saturating_sub(self.offset))
+    }
+}
+
+impl<R: Write> Write for OffsetReader<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Combines a password with the digest of a key file, so that decryption requires both
+/// "something you know" and "something you have". The password and the file digest are
+/// domain-separated with a fixed label and NUL bytes, so neither can be forged from the other.
+fn combine_with_key_file(
+    password: &Zeroizing<String>,
+    key_file: &Path,
+) -> Result<Zeroizing<String>, MainStatus> {
+    let contents = Zeroizing::new(
+        fs::read(key_file)
+            .map_err(|_| MainStatus::Err(format!("Unable to read key file {:?}", key_file)))?,
+    );
+    let mut hasher = Sha256::new();
+    hasher.update(&*contents);
+    let mut digest = hasher.finalize();
+    let combined = Zeroizing::new(format!(
+        "zeppelin-keyfile-v1\0{}\0{:x}",
+        password.as_str(),
+        digest
+    ));
+    // A plain zeroing loop can be dead-store-eliminated by the optimizer; `Zeroize` guarantees
+    // the write isn't.
+    digest.as_mut_slice().zeroize();
+    Ok(combined)
+}
+
+/// Securely erases the file at `path` by overwriting its full length with a few passes
+/// (random bytes, then zeros), flushing and syncing between each, then truncating and
+/// removing it. Best-effort: on filesystems with copy-on-write or wear-leveling this
+/// does not guarantee the old blocks are gone, but it's the standard precaution.
+fn secure_erase(path: &Path) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    // Two random passes, then a final all-zero pass.
+    for pass in 0..3 {
+        file.seek(SeekFrom::Start(0))?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(buf.len() as u64) as usize;
+            if pass < 2 {
+                OsRng.fill_bytes(&mut buf[..chunk]);
+            } else {
+                buf[..chunk].fill(0);
+            }
+            file.write_all(&buf[..chunk])?;
+            remaining -= chunk as u64;
+        }
+        file.flush()?;
+        file.sync_all()?;
+    }
+
+    file.set_len(0)?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)
+}
+
+/// Returns whether the process can fall back to an interactive prompt,
+/// i.e. stdin is attached to a real terminal.
+fn is_interactive() -> bool {
+    console::Term::stdin().features().is_attended()
+}
+
+/// Generates a random password of `len` characters, resampling until it contains at least
+/// one uppercase letter, lowercase letter, digit and symbol (impossible to guarantee below
+/// four characters, so shorter passwords skip that check).
+fn generate_character_password(len: usize) -> Zeroizing<String> {
+    const UPPER: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    const LOWER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+    const DIGIT: &[u8] = b"0123456789";
+    const SYMBOL: &[u8] = b"!@#$%^&*()-_=+[]{};:,.<>?/";
+    let alphabet: Vec<u8> = [UPPER, LOWER, DIGIT, SYMBOL].concat();
+
+    loop {
+        let candidate: Vec<u8> = (0..len)
+            .map(|_| alphabet[OsRng.gen_range(0..alphabet.len())])
+            .collect();
+
+        let has_all_classes = len < 4
+            || (candidate.iter().any(|b| UPPER.contains(b))
+                && candidate.iter().any(|b| LOWER.contains(b))
+                && candidate.iter().any(|b| DIGIT.contains(b))
+                && candidate.iter().any(|b| SYMBOL.contains(b)));
+
+        if has_all_classes {
+            return Zeroizing::new(String::from_utf8(candidate).expect("alphabet is ASCII"));
+        }
+    }
+}
+
+/// Generates a diceware-style passphrase: `words` entries drawn uniformly at random from
+/// `wordlist`, joined with spaces.
+fn generate_diceware_password(wordlist: &Path, words: usize) -> Result<Zeroizing<String>, MainStatus> {
+    let contents = fs::read_to_string(wordlist)
+        .map_err(|_| MainStatus::Err(format!("Unable to read wordlist {:?}", wordlist)))?;
+    let candidates: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if candidates.is_empty() {
+        return Err(MainStatus::Err(format!(
+            "Wordlist {:?} doesn't contain any words",
+            wordlist
+        )));
+    }
+
+    let passphrase = (0..words)
+        .map(|_| *candidates.choose(&mut OsRng).expect("checked non-empty above"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok(Zeroizing::new(passphrase))
+}
+
+/// Blocks until `thread` finishes, rendering a live progress bar on stderr unless `quiet`
+/// is set (e.g. because the container is being streamed to a pipe), then joins it.
+fn wait_for_thread(
+    thread: thread::JoinHandle<MainStatus>,
+    progress: Progress,
+    quiet: bool,
+) -> MainStatus {
+    if quiet {
+        while !thread.is_finished() {
+            thread::sleep(Duration::from_millis(1000 / 10));
+        }
+    } else {
+        let mut out = console::Term::buffered_stderr();
+        out.hide_cursor().unwrap();
+        while !thread.is_finished() {
+            let (h, w) = out.size();
+            progress::print_progress_bar(&mut out, h, w, progress.clone()).unwrap();
+            // Refresh progress bar ~ 10 times / second
+            thread::sleep(Duration::from_millis(1000 / 10));
+        }
     }
 
-    // Check if file extension indicates that file should be decrypted
-    let mut decrypt = if let Some(extension) = file.extension() {
-        extension == "zep"
+    if let Ok(status) = thread.join() {
+        status
+    } else {
+        MainStatus::Err(String::from("Unable to join thread!"))
+    }
+}
+
+/// Rotates the password of an existing container in place. `create_container` wraps a random
+/// master key under a password-derived KEK in the header, so rotating the password only means
+/// unwrapping that master key with the old KEK and re-wrapping it under a freshly derived one;
+/// the bulk ciphertext is never touched.
+fn run_change_password(mut args: Args, source: PathBuf) -> MainStatus {
+    let old_key: Zeroizing<String> = if let Some(password) = args.password.take() {
+        Zeroizing::new(password)
+    } else if let Ok(password) = dialoguer::Password::new()
+        .with_prompt("Current password")
+        .interact()
+    {
+        Zeroizing::new(password)
+    } else {
+        return MainStatus::Err(String::from("Unable to read password!"));
+    };
+
+    let old_key = if let Some(key_file) = &args.key_file {
+        match combine_with_key_file(&old_key, key_file) {
+            Ok(combined) => combined,
+            Err(status) => return status,
+        }
     } else {
-        false
+        old_key
     };
 
+    let new_key: Zeroizing<String> = if let Some(password) = args.new_password.take() {
+        Zeroizing::new(password)
+    } else if let Ok(password) = dialoguer::Password::new()
+        .with_prompt("New password")
+        .with_confirmation("Confirm new password", "Passwords didn't match")
+        .interact()
+    {
+        Zeroizing::new(password)
+    } else {
+        return MainStatus::Err(String::from("Unable to read password!"));
+    };
+
+    // Fold the key file into the new key too, so rotating the password can't silently drop
+    // the "something you have" factor from a two-factor container.
+    let new_key = if let Some(key_file) = &args.key_file {
+        match combine_with_key_file(&new_key, key_file) {
+            Ok(combined) => combined,
+            Err(status) => return status,
+        }
+    } else {
+        new_key
+    };
+
+    // Skip past the 1-byte "key file required" header this CLI prepends to every container
+    // (see the encrypt/decrypt paths in `main`), so `change_password` sees the real container
+    // header starting at its expected offset 0. `OffsetReader` also implements `Write`, so the
+    // in-place rewrite lands at the right place too, leaving the leading byte untouched.
+    let mut file = match fs::OpenOptions::new().read(true).write(true).open(&source) {
+        Ok(file) => file,
+        Err(_) => return MainStatus::Err(String::from("Unable to open file")),
+    };
+    let mut key_file_required = [0u8];
+    if file.read_exact(&mut key_file_required).is_err() {
+        return MainStatus::Err("Container invalid!".to_string());
+    }
+    if key_file_required[0] != 0 && args.key_file.is_none() {
+        return MainStatus::KeyFileRequired;
+    }
+    let mut container = OffsetReader {
+        inner: file,
+        offset: 1,
+    };
+
+    let progress = Progress::new();
+    progress.set_state("Starting".to_string());
+    let thread = thread::spawn({
+        let thread_progress = progress.clone();
+        move || match change_password(&mut container, old_key, new_key, Some(thread_progress)) {
+            Ok(true) => MainStatus::Ok,
+            Ok(false) => MainStatus::InvalidPassword,
+            Err(_) => MainStatus::Err("Container invalid!".to_string()),
+        }
+    });
+
+    wait_for_thread(thread, progress, false)
+}
+
+/// Copies all of stdin into a temporary file and seeks it back to the start, so it can be
+/// used anywhere a `Seek`-able reader is required (`read_container` seeks to verify the
+/// container before decrypting it).
+fn spill_stdin_to_tempfile() -> io::Result<fs::File> {
+    let mut spill = tempfile::tempfile()?;
+    io::copy(&mut io::stdin(), &mut spill)?;
+    spill.seek(SeekFrom::Start(0))?;
+    Ok(spill)
+}
+
+/// Opens the writer side of the container: the given path, or stdout when none was given.
+fn make_writer(output_path: &Option<PathBuf>) -> Result<Box<dyn Write + Send>, MainStatus> {
+    match output_path {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Ok(Box::new(io::BufWriter::new(file))),
+            Err(_) => Err(MainStatus::Err(format!("Could not access {:?}", path))),
+        },
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/// Builds the `CryptSettings` to encrypt with, from `--level`/`--s-cost`/`--t-cost`/`--step-delta`
+/// when given, only falling back to the interactive menu when nothing was specified and stdin
+/// is a TTY.
+fn build_crypt_settings(args: &Args) -> Result<CryptSettings, MainStatus> {
+    let explicit = args.level.is_some()
+        || args.s_cost.is_some()
+        || args.t_cost.is_some()
+        || args.step_delta.is_some();
+
+    if !explicit {
+        if !is_interactive() {
+            return Err(MainStatus::Err(
+                "No --level given and stdin is not a TTY; refusing to prompt".to_string(),
+            ));
+        }
+        return prompt_crypt_settings();
+    }
+
+    let level = args.level.unwrap_or(Level::Custom);
+    Ok(match level {
+        Level::Weak => CryptSettings::default_for_testing(),
+        Level::Default => CryptSettings::default(),
+        Level::Strong => CryptSettings {
+            s_cost: 468750 * 10,
+            t_cost: 3,
+            step_delta: 4,
+        },
+        Level::Custom => {
+            let defaults = CryptSettings::default();
+            CryptSettings {
+                s_cost: args.s_cost.unwrap_or(defaults.s_cost as u64) as usize,
+                t_cost: args.t_cost.unwrap_or(defaults.t_cost as u64) as usize,
+                step_delta: args.step_delta.unwrap_or(defaults.step_delta as u64) as usize,
+            }
+        }
+    })
+}
+
+/// Interactively asks the user for the encryption level, falling back to custom prompts
+/// for the individual cost parameters.
+fn prompt_crypt_settings() -> Result<CryptSettings, MainStatus> {
+    let choices = vec!["Weak", "Default", "Strong", "Custom"];
+
+    let choice = dialoguer::Select::new()
+        .with_prompt("Select an encryption level")
+        .items(&choices)
+        .default(1)
+        .interact()
+        .unwrap();
+
+    Ok(match choice {
+        0 => CryptSettings::default_for_testing(),
+        1 => CryptSettings::default(),
+        2 => CryptSettings {
+            s_cost: 468750 * 10,
+            t_cost: 3,
+            step_delta: 4,
+        },
+        3 => {
+            let s_cost = if let Ok(val) = dialoguer::Input::<usize>::new()
+                .with_prompt(format!(
+                    "s_cost (default: {})",
+                    CryptSettings::default().s_cost
+                ))
+                .interact()
+            {
+                val
+            } else {
+                return Err(MainStatus::Err("Unable to get user prompt!".to_string()));
+            };
+            let t_cost = if let Ok(val) = dialoguer::Input::<usize>::new()
+                .with_prompt(format!(
+                    "t_cost (default: {})",
+                    CryptSettings::default().t_cost
+                ))
+                .interact()
+            {
+                val
+            } else {
+                return Err(MainStatus::Err("Unable to get user prompt!".to_string()));
+            };
+            let step_delta = if let Ok(val) = dialoguer::Input::<usize>::new()
+                .with_prompt(format!(
+                    "step_delta (default: {})",
+                    CryptSettings::default().step_delta
+                ))
+                .interact()
+            {
+                val
+            } else {
+                return Err(MainStatus::Err("Unable to get user prompt!".to_string()));
+            };
+            CryptSettings {
+                s_cost,
+                t_cost,
+                step_delta,
+            }
+        }
+        _ => {
+            return Err(MainStatus::Err("Invalid Choice".to_string()));
+        }
+    })
+}
+
+fn main() -> MainStatus {
+    let mut args = Args::parse();
+
+    // A bare `-`, or omitting the path entirely, means "read from stdin".
+    let source_path = args
+        .file
+        .clone()
+        .filter(|path| path.as_os_str() != "-");
+
+    // Check if given file exists
+    if let Some(path) = &source_path {
+        if !path.exists() {
+            return MainStatus::FileNotFound(path.clone());
+        }
+    }
+
+    // A key file, if given, must exist up front: we need its contents to derive the key.
+    if let Some(key_file) = &args.key_file {
+        if !key_file.exists() {
+            return MainStatus::FileNotFound(key_file.clone());
+        }
+    }
+
+    if args.change_password {
+        let Some(source) = source_path else {
+            return MainStatus::Err(
+                "--change-password requires a real file path, not stdin".to_string(),
+            );
+        };
+        return run_change_password(args, source);
+    }
+
+    // Check if file extension indicates that file should be decrypted (stdin has none)
+    let mut decrypt = source_path
+        .as_ref()
+        .and_then(|path| path.extension())
+        .map(|ext| ext == "zep")
+        .unwrap_or(false);
+
     // Let user override the automatically detected value
     if args.decrypt {
         decrypt = true
     }
 
-    // Try to open file
-    let file = if let Ok(inner) = fs::File::open(file) {
-        inner
-    } else {
-        return MainStatus::Err(String::from("Unable to open file"));
-    };
-    let mut file = io::BufReader::new(file);
+    // Write the container to stdout when no output path was given and stdout is a pipe,
+    // mirroring how the input side treats stdin.
+    let writing_stdout = args.output.is_none() && !console::Term::stdout().features().is_attended();
 
-    // Choose appropriate name for output file
-    let output_path = if let Some(path) = args.output {
-        path
-    } else {
-        let mut tmp = args.file.clone();
+    // Choose appropriate name for output file, unless we're just streaming to a pipe
+    let output_path = if writing_stdout {
+        None
+    } else if let Some(path) = args.output.clone() {
+        Some(path)
+    } else if let Some(source) = &source_path {
+        let mut tmp = source.clone();
         if decrypt {
             if let Some(ext) = tmp.extension() {
                 if ext == "zep" {
                     tmp.set_extension("");
-                    tmp
                 } else {
                     append_extension(&mut tmp, "unzep");
-                    tmp
                 }
             } else {
                 tmp.set_extension("unzep");
-                tmp
             }
         } else {
             append_extension(&mut tmp, "zep");
-            tmp
         }
+        Some(tmp)
+    } else {
+        return MainStatus::Err(
+            "An output path is required when reading from stdin on a terminal".to_string(),
+        );
     };
 
     // Sanity check, to make sure we don't write to file we are reading
     // `output_path` should have been chosen in a way to be distinct from `file`
     // but better safe than sorry.
-    if output_path == args.file {
-        return MainStatus::Err("Input and output file should be different".to_string());
-    }
+    if let Some(output_path) = &output_path {
+        if Some(output_path) == source_path.as_ref() {
+            return MainStatus::Err("Input and output file should be different".to_string());
+        }
 
-    if output_path.exists() {
-        if let Ok(confirmation) = dialoguer::Confirm::new()
-            .with_prompt(format!(
-                "Are you sure you want to override {:?}",
-                output_path
-            ))
-            .interact()
-        {
-            if !confirmation {
-                return MainStatus::Err("Operation cancelled!".to_string());
+        if output_path.exists() && !args.yes {
+            if let Ok(confirmation) = dialoguer::Confirm::new()
+                .with_prompt(format!(
+                    "Are you sure you want to override {:?}",
+                    output_path
+                ))
+                .interact()
+            {
+                if !confirmation {
+                    return MainStatus::Err("Operation cancelled!".to_string());
+                }
+            } else {
+                return MainStatus::Err("Unable to get user prompt!".to_string());
             }
-        } else {
-            return MainStatus::Err("Unable to get user prompt!".to_string());
         }
     }
 
-    // Allocate output file
-    let output = if let Ok(file) = fs::File::create(&output_path) {
-        file
-    } else {
-        return MainStatus::Err(format!("Could not access {:?}", output_path));
-    };
-    let mut output = io::BufWriter::new(output);
-
-    // Request password from user
-    let key = if let Ok(password) = dialoguer::Password::new()
+    // Request password from user, unless one was given on the command line or we were asked to
+    // generate one. Wrapped in `Zeroizing` the moment it's ours, so it (and everything derived
+    // from it) is wiped from memory on every exit path, including the early returns below.
+    let key: Zeroizing<String> = if let Some(len) = args.generate_password {
+        if decrypt {
+            return MainStatus::Err(
+                "--generate-password only applies when encrypting".to_string(),
+            );
+        }
+        let generated = match &args.diceware {
+            Some(wordlist) => match generate_diceware_password(wordlist, len) {
+                Ok(passphrase) => passphrase,
+                Err(status) => return status,
+            },
+            None => generate_character_password(len),
+        };
+        eprintln!("Generated password: {}", *generated);
+        generated
+    } else if let Some(password) = args.password.take() {
+        Zeroizing::new(password)
+    } else if let Ok(password) = dialoguer::Password::new()
         .with_prompt("Password")
         .interact()
     {
-        password
+        Zeroizing::new(password)
     } else {
         return MainStatus::Err(String::from("Unable to read password!"));
     };
 
+    // Fold the key file into the key, if one was given. Whether a key file was required is
+    // also recorded in a 1-byte header in front of the container (see `OffsetReader`), so a
+    // missing key file on decrypt is reported clearly instead of as a generic wrong password.
+    let key = if let Some(key_file) = &args.key_file {
+        match combine_with_key_file(&key, key_file) {
+            Ok(combined) => combined,
+            Err(status) => return status,
+        }
+    } else {
+        key
+    };
+
     // Start the encryption/decryption thread
     let progress = Progress::new();
     progress.set_state("Starting".to_string());
     let thread = if decrypt {
-        // Decrypt
+        // Decrypt needs a seekable reader to verify the container before writing anything out.
+        // Real files are already seekable; stdin isn't, so we spill it to a temp file first.
+        let mut file = match &source_path {
+            Some(path) => match fs::File::open(path) {
+                Ok(file) => file,
+                Err(_) => return MainStatus::Err(String::from("Unable to open file")),
+            },
+            None => match spill_stdin_to_tempfile() {
+                Ok(file) => file,
+                Err(err) => {
+                    return MainStatus::Err(format!("Unable to buffer stdin: {}", err))
+                }
+            },
+        };
+
+        // Read the 1-byte header recording whether this container requires a key file, before
+        // handing the rest of the stream to `read_container`.
+        let mut key_file_required = [0u8];
+        if file.read_exact(&mut key_file_required).is_err() {
+            return MainStatus::Err("Container invalid!".to_string());
+        }
+        if key_file_required[0] != 0 && args.key_file.is_none() {
+            return MainStatus::KeyFileRequired;
+        }
+
+        let mut reader = OffsetReader {
+            inner: io::BufReader::new(file),
+            offset: 1,
+        };
+
+        let mut writer = match make_writer(&output_path) {
+            Ok(writer) => writer,
+            Err(status) => return status,
+        };
+
         thread::spawn({
             let thread_progress = progress.clone();
             move || {
                 if let Ok(decrypted) =
-                    read_container(&mut file, &mut output, key, Some(thread_progress))
+                    read_container(&mut reader, &mut writer, key, Some(thread_progress))
                 {
                     if decrypted {
                         MainStatus::Ok
@@ -213,72 +718,36 @@ fn main() -> MainStatus {
         })
     } else {
         // Encrypt
-        let choices = vec!["Weak", "Default", "Strong", "Custom"];
-
-        let choice = dialoguer::Select::new()
-            .with_prompt("Select an encryption level")
-            .items(&choices)
-            .default(1)
-            .interact()
-            .unwrap();
-
-        let settings = match choice {
-            0 => CryptSettings::default_for_testing(),
-            1 => CryptSettings::default(),
-            2 => CryptSettings {
-                s_cost: 468750 * 10,
-                t_cost: 3,
-                step_delta: 4,
+        let settings = match build_crypt_settings(&args) {
+            Ok(settings) => settings,
+            Err(status) => return status,
+        };
+
+        let mut reader: Box<dyn Read + Send> = match &source_path {
+            Some(path) => match fs::File::open(path) {
+                Ok(file) => Box::new(io::BufReader::new(file)),
+                Err(_) => return MainStatus::Err(String::from("Unable to open file")),
             },
-            3 => {
-                let s_cost = if let Ok(val) = dialoguer::Input::<usize>::new()
-                    .with_prompt(format!(
-                        "s_cost (default: {})",
-                        CryptSettings::default().s_cost
-                    ))
-                    .interact()
-                {
-                    val
-                } else {
-                    return MainStatus::Err("Unable to get user prompt!".to_string());
-                };
-                let t_cost = if let Ok(val) = dialoguer::Input::<usize>::new()
-                    .with_prompt(format!(
-                        "t_cost (default: {})",
-                        CryptSettings::default().t_cost
-                    ))
-                    .interact()
-                {
-                    val
-                } else {
-                    return MainStatus::Err("Unable to get user prompt!".to_string());
-                };
-                let step_delta = if let Ok(val) = dialoguer::Input::<usize>::new()
-                    .with_prompt(format!(
-                        "step_delta (default: {})",
-                        CryptSettings::default().step_delta
-                    ))
-                    .interact()
-                {
-                    val
-                } else {
-                    return MainStatus::Err("Unable to get user prompt!".to_string());
-                };
-                CryptSettings {
-                    s_cost,
-                    t_cost,
-                    step_delta,
-                }
-            }
-            _ => {
-                return MainStatus::Err("Invalid Choice".to_string());
-            }
+            None => Box::new(io::stdin()),
         };
 
+        let mut writer = match make_writer(&output_path) {
+            Ok(writer) => writer,
+            Err(status) => return status,
+        };
+
+        // Record whether this container requires a key file in a 1-byte header, ahead of the
+        // real container bytes (see `OffsetReader`), so a missing key file on decrypt can be
+        // reported clearly instead of surfacing as a generic wrong password.
+        let key_file_required = if args.key_file.is_some() { 1u8 } else { 0u8 };
+        if let Err(err) = writer.write_all(&[key_file_required]) {
+            return MainStatus::Err(format!("Could not write container header: {}", err));
+        }
+
         thread::spawn({
             let thread_progress = progress.clone();
             move || {
-                if create_container(&mut file, &mut output, key, settings, Some(thread_progress))
+                if create_container(&mut reader, &mut writer, key, settings, Some(thread_progress))
                     .is_ok()
                 {
                     MainStatus::Ok
@@ -289,19 +758,19 @@ fn main() -> MainStatus {
         })
     };
 
-    // Monitor the progress of the thread; printing a progress bar
-    let mut out = console::Term::buffered_stderr();
-    out.hide_cursor().unwrap();
-    while !thread.is_finished() {
-        let (h, w) = out.size();
-        progress::print_progress_bar(&mut out, h, w, progress.clone()).unwrap();
-        // Refresh progress bar ~ 10 times / second
-        thread::sleep(Duration::from_millis(1000 / 10));
-    }
+    // Monitor the progress of the thread, printing a progress bar. Skipped when streaming the
+    // container to a pipe, so we don't mix escape codes into whatever is consuming stdout.
+    let thread_status = wait_for_thread(thread, progress, writing_stdout);
 
-    if let Ok(thread_status) = thread.join() {
-        thread_status
-    } else {
-        MainStatus::Err(String::from("Unable to join thread!"))
+    // Securely erase the original plaintext once it has been encrypted successfully.
+    // Never applies to decryption, nor when reading from stdin (there's no file to erase).
+    if args.erase && !decrypt && matches!(thread_status, MainStatus::Ok) {
+        if let Some(source) = &source_path {
+            if let Err(err) = secure_erase(source) {
+                return MainStatus::EraseFailed(err.to_string());
+            }
+        }
     }
+
+    thread_status
 }